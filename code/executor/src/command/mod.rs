@@ -0,0 +1,22 @@
+use anyhow::Result;
+use protocol::{ClientPacket, ServerPacket};
+
+use crate::Executor;
+
+mod pty;
+pub mod rpc;
+
+pub use pty::run_pty_session;
+
+pub async fn handle(_executor: &Executor, packet: ClientPacket) -> Result<ServerPacket> {
+    match packet {
+        ClientPacket::Text(text) => Ok(ServerPacket::Text(text)),
+        ClientPacket::Binary(bytes) => Ok(ServerPacket::Binary(bytes)),
+    }
+}
+
+/// Whether `packet` is a request to switch the connection into interactive
+/// PTY mode (see [`run_pty_session`]).
+pub fn is_pty_request(packet: &ClientPacket) -> bool {
+    matches!(packet, ClientPacket::Text(text) if text == "pty")
+}
@@ -0,0 +1,172 @@
+use std::io::{Read, Write};
+
+use anyhow::{Context, Result};
+use portable_pty::{native_pty_system, CommandBuilder, PtySize};
+use protocol::{ClientPacket, ServerPacket};
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::{CommRx, CommTx};
+
+const OPCODE_INPUT: u8 = 0;
+const OPCODE_RESIZE: u8 = 1;
+
+#[derive(Debug, PartialEq, Deserialize)]
+struct Resize {
+    cols: u16,
+    rows: u16,
+}
+
+/// Run an interactive shell for the lifetime of the connection, forwarding
+/// PTY output as binary [`ServerPacket`]s and decoding opcode-tagged binary
+/// [`ClientPacket`]s back into PTY input (`0`) or a resize (`1`).
+///
+/// The PTY and child process are closed/reaped before this returns, whether
+/// that's because the child exited or the `Comm` stream ended.
+pub async fn run_pty_session(mut tx: impl CommTx, mut rx: impl CommRx) -> Result<()> {
+    let pty_system = native_pty_system();
+    let pair = pty_system
+        .openpty(PtySize {
+            rows: 24,
+            cols: 80,
+            pixel_width: 0,
+            pixel_height: 0,
+        })
+        .context("Failed to open PTY")?;
+
+    let mut child = pair
+        .slave
+        .spawn_command(CommandBuilder::new_default_prog())
+        .context("Failed to spawn shell")?;
+    drop(pair.slave);
+
+    let mut writer = pair
+        .master
+        .take_writer()
+        .context("Failed to take PTY writer")?;
+    let mut reader = pair
+        .master
+        .try_clone_reader()
+        .context("Failed to clone PTY reader")?;
+
+    let (output_tx, mut output_rx) = tokio::sync::mpsc::unbounded_channel::<Vec<u8>>();
+    let reader_task = tokio::task::spawn_blocking(move || {
+        let mut buf = [0u8; 4096];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    if output_tx.send(buf[..n].to_vec()).is_err() {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+    });
+
+    let result = loop {
+        tokio::select! {
+            chunk = output_rx.recv() => {
+                match chunk {
+                    Some(chunk) => {
+                        if let Err(e) = tx.send(ServerPacket::Binary(chunk)).await {
+                            break Err(e);
+                        }
+                    }
+                    None => break Ok(()),
+                }
+            }
+            packet = rx.recv() => {
+                match packet {
+                    Ok(ClientPacket::Binary(bytes)) => {
+                        if let Err(e) = handle_frame(&bytes, &mut writer, &pair.master) {
+                            warn!("Dropping malformed PTY frame: {e}");
+                        }
+                    }
+                    Ok(_) => continue,
+                    Err(e) => break Err(e),
+                }
+            }
+        }
+    };
+
+    drop(writer);
+    child.kill().ok();
+    let _ = tokio::task::spawn_blocking(move || child.wait()).await;
+    reader_task.abort();
+
+    result
+}
+
+/// A decoded, opcode-tagged PTY frame (see [`parse_frame`]).
+#[derive(Debug, PartialEq)]
+enum Frame {
+    Input(Vec<u8>),
+    Resize(Resize),
+}
+
+/// Split a binary `ClientPacket` into its opcode and parse its payload,
+/// without touching the PTY itself — kept separate from [`handle_frame`] so
+/// it can be unit tested without a real `MasterPty`/writer.
+fn parse_frame(bytes: &[u8]) -> Result<Frame> {
+    let (&opcode, payload) = bytes.split_first().context("Empty PTY frame")?;
+
+    match opcode {
+        OPCODE_INPUT => Ok(Frame::Input(payload.to_vec())),
+        OPCODE_RESIZE => Ok(Frame::Resize(serde_json::from_slice(payload)?)),
+        other => anyhow::bail!("Unknown PTY opcode: {other}"),
+    }
+}
+
+fn handle_frame(
+    bytes: &[u8],
+    writer: &mut Box<dyn Write + Send>,
+    master: &Box<dyn portable_pty::MasterPty + Send>,
+) -> Result<()> {
+    match parse_frame(bytes)? {
+        Frame::Input(payload) => writer.write_all(&payload)?,
+        Frame::Resize(resize) => master.resize(PtySize {
+            rows: resize.rows,
+            cols: resize.cols,
+            pixel_width: 0,
+            pixel_height: 0,
+        })?,
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_frame_rejects_empty_input() {
+        assert!(parse_frame(&[]).is_err());
+    }
+
+    #[test]
+    fn parse_frame_decodes_input() {
+        let bytes = [&[OPCODE_INPUT][..], b"hello"].concat();
+        assert_eq!(
+            parse_frame(&bytes).unwrap(),
+            Frame::Input(b"hello".to_vec())
+        );
+    }
+
+    #[test]
+    fn parse_frame_decodes_resize() {
+        let bytes = [&[OPCODE_RESIZE][..], br#"{"cols":80,"rows":24}"#].concat();
+        assert_eq!(
+            parse_frame(&bytes).unwrap(),
+            Frame::Resize(Resize { cols: 80, rows: 24 })
+        );
+    }
+
+    #[test]
+    fn parse_frame_rejects_unknown_opcode() {
+        let bytes = [&[0xFF][..], b"x"].concat();
+        assert!(parse_frame(&bytes).is_err());
+    }
+}
@@ -0,0 +1,334 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use anyhow::{Context, Result};
+use dashmap::DashMap;
+use protocol::{ClientPacket, ServerPacket};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{sync::Mutex, time::Duration};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+
+use crate::{command, CommTx, ConnectionId, Executor};
+
+/// A single JSON-RPC 2.0 style request, carried as JSON inside a
+/// [`ClientPacket::Text`]. A text packet may also hold an array of these
+/// for batched requests.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Request {
+    pub id: Option<Value>,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+enum Reply {
+    Response {
+        id: Option<Value>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        result: Option<Value>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    Notification {
+        subscription: String,
+        result: Value,
+    },
+    Registered {
+        connection_id: ConnectionId,
+    },
+}
+
+/// Envelope wrapping a `send_to`/`broadcast` payload with the
+/// [`ConnectionId`] of the connection that sent it, so the recipient can
+/// tell who addressed them (and reply via its own `send_to`).
+#[derive(Serialize)]
+struct Envelope {
+    from: ConnectionId,
+    payload: Value,
+}
+
+/// The send half of a connection, shared between the main request loop and
+/// any background subscription tasks it spawns.
+pub type SharedTx = Arc<Mutex<Box<dyn CommTx + Send>>>;
+
+/// Tell a freshly registered connection its own [`ConnectionId`], so it can
+/// pass it to another connection as the `to` of a `send_to` call.
+pub async fn notify_registered(tx: &SharedTx, connection_id: ConnectionId) -> Result<()> {
+    let text = serde_json::to_string(&Reply::Registered { connection_id })?;
+    tx.lock().await.send(ServerPacket::Text(text)).await
+}
+
+/// Parse a text packet into one or more RPC requests, per the JSON-RPC
+/// batching rules (a bare object, or an array of objects).
+pub fn parse_batch(packet: &ClientPacket) -> Result<Vec<Request>> {
+    let ClientPacket::Text(text) = packet else {
+        anyhow::bail!("RPC layer only accepts text packets");
+    };
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Batch {
+        One(Request),
+        Many(Vec<Request>),
+    }
+
+    Ok(
+        match serde_json::from_str(text).context("Invalid RPC request")? {
+            Batch::One(request) => vec![request],
+            Batch::Many(requests) => requests,
+        },
+    )
+}
+
+async fn reply(tx: &SharedTx, reply: Reply) -> Result<()> {
+    let text = serde_json::to_string(&reply)?;
+    tx.lock().await.send(ServerPacket::Text(text)).await
+}
+
+/// Tracks this connection's open subscriptions so `unsubscribe` (or
+/// connection teardown) can cancel the background task pushing
+/// notifications for each one.
+///
+/// Backed by a [`DashMap`] rather than a plain `HashMap` so concurrently
+/// dispatched requests (see [`dispatch`]) can `subscribe`/`unsubscribe`
+/// without serializing on a single connection-wide lock.
+#[derive(Default)]
+pub struct Subscriptions {
+    next_id: AtomicU64,
+    active: DashMap<String, CancellationToken>,
+}
+
+impl Subscriptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel every open subscription's background task. Called once the
+    /// connection's `run_loop` exits for any reason, so a disconnect (or a
+    /// switch to PTY mode) doesn't leave `run_subscription` tasks polling
+    /// forever for a client that's no longer listening.
+    pub fn cancel_all(&self) {
+        for entry in self.active.iter() {
+            entry.value().cancel();
+        }
+        self.active.clear();
+    }
+}
+
+fn packet_to_value(packet: ServerPacket) -> Value {
+    match packet {
+        ServerPacket::Text(text) => serde_json::from_str(&text).unwrap_or(Value::String(text)),
+        ServerPacket::Binary(bytes) => Value::String(format!("<{} bytes>", bytes.len())),
+    }
+}
+
+/// Dispatch a single RPC request: a plain call gets one `Response`; a
+/// `subscribe` call gets a `Response` carrying the new subscription id,
+/// followed by a `Notification` on `tx` each time [`run_subscription`]
+/// re-runs the subscribed method (whether or not its value changed), until
+/// `unsubscribe` cancels it.
+///
+/// `from` is the dispatching connection's own id, stamped onto any
+/// `send_to`/`broadcast` payload so the recipient knows who it's from.
+///
+/// `tasks` tracks the background poll loop a `subscribe` call spawns, the
+/// same tracker the caller uses for the per-request dispatch task itself,
+/// so a connection's teardown can wait for both kinds of task together.
+pub async fn dispatch(
+    executor: &Executor,
+    request: Request,
+    tx: &SharedTx,
+    subs: &Subscriptions,
+    tasks: &TaskTracker,
+    from: ConnectionId,
+) -> Result<()> {
+    match request.method.as_str() {
+        "subscribe" => {
+            let sub_id = format!("sub-{}", subs.next_id.fetch_add(1, Ordering::Relaxed));
+            let token = CancellationToken::new();
+            subs.active.insert(sub_id.clone(), token.clone());
+
+            reply(
+                tx,
+                Reply::Response {
+                    id: request.id,
+                    result: Some(Value::String(sub_id.clone())),
+                    error: None,
+                },
+            )
+            .await?;
+
+            tasks.spawn(run_subscription(
+                executor.clone(),
+                tx.clone(),
+                sub_id,
+                request.params,
+                token,
+            ));
+
+            Ok(())
+        }
+        "unsubscribe" => {
+            let sub_id = request
+                .params
+                .get("subscription")
+                .and_then(Value::as_str)
+                .context("`unsubscribe` requires a `subscription` id")?;
+
+            if let Some((_, token)) = subs.active.remove(sub_id) {
+                token.cancel();
+            }
+
+            reply(
+                tx,
+                Reply::Response {
+                    id: request.id,
+                    result: Some(Value::Bool(true)),
+                    error: None,
+                },
+            )
+            .await
+        }
+        "send_to" => {
+            let to = request
+                .params
+                .get("to")
+                .and_then(Value::as_u64)
+                .context("`send_to` requires a numeric `to` connection id")?;
+            let payload = request
+                .params
+                .get("payload")
+                .cloned()
+                .unwrap_or(Value::Null);
+
+            let envelope = serde_json::to_string(&Envelope { from, payload })?;
+            let delivered = executor.send_to(to, ServerPacket::Text(envelope)).await;
+
+            reply(
+                tx,
+                Reply::Response {
+                    id: request.id,
+                    result: Some(Value::Bool(delivered)),
+                    error: None,
+                },
+            )
+            .await
+        }
+        "broadcast" => {
+            let payload = request
+                .params
+                .get("payload")
+                .cloned()
+                .unwrap_or(Value::Null);
+            let envelope = serde_json::to_string(&Envelope { from, payload })?;
+            executor.broadcast(ServerPacket::Text(envelope)).await;
+
+            reply(
+                tx,
+                Reply::Response {
+                    id: request.id,
+                    result: Some(Value::Bool(true)),
+                    error: None,
+                },
+            )
+            .await
+        }
+        _ => {
+            let packet = ClientPacket::Text(request.params.to_string());
+            let (result, error) = match command::handle(executor, packet).await {
+                Ok(packet) => (Some(packet_to_value(packet)), None),
+                Err(e) => (None, Some(e.to_string())),
+            };
+
+            reply(
+                tx,
+                Reply::Response {
+                    id: request.id,
+                    result,
+                    error,
+                },
+            )
+            .await
+        }
+    }
+}
+
+/// Re-runs `params` through [`command::handle`] on a fixed interval,
+/// pushing each result as a notification until `token` is cancelled or the
+/// connection goes away.
+async fn run_subscription(
+    executor: Executor,
+    tx: SharedTx,
+    sub_id: String,
+    params: Value,
+    token: CancellationToken,
+) {
+    let mut interval = tokio::time::interval(Duration::from_millis(250));
+
+    loop {
+        tokio::select! {
+            () = token.cancelled() => break,
+            _ = interval.tick() => {
+                let packet = ClientPacket::Text(params.to_string());
+                let result = match command::handle(&executor, packet).await {
+                    Ok(packet) => packet_to_value(packet),
+                    Err(e) => Value::String(format!("error: {e}")),
+                };
+
+                let notification = Reply::Notification {
+                    subscription: sub_id.clone(),
+                    result,
+                };
+
+                if reply(&tx, notification).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_batch_rejects_binary_packets() {
+        let packet = ClientPacket::Binary(vec![1, 2, 3]);
+        assert!(parse_batch(&packet).is_err());
+    }
+
+    #[test]
+    fn parse_batch_accepts_a_bare_object() {
+        let packet = ClientPacket::Text(r#"{"method": "ping"}"#.into());
+        let requests = parse_batch(&packet).unwrap();
+
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "ping");
+        assert_eq!(requests[0].id, None);
+    }
+
+    #[test]
+    fn parse_batch_accepts_an_array() {
+        let packet = ClientPacket::Text(
+            r#"[{"method": "ping"}, {"id": 1, "method": "subscribe", "params": {}}]"#.into(),
+        );
+        let requests = parse_batch(&packet).unwrap();
+
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].method, "ping");
+        assert_eq!(requests[1].method, "subscribe");
+        assert_eq!(requests[1].id, Some(Value::from(1)));
+    }
+
+    #[test]
+    fn parse_batch_rejects_invalid_json() {
+        let packet = ClientPacket::Text("not json".into());
+        assert!(parse_batch(&packet).is_err());
+    }
+}
@@ -1,22 +1,28 @@
 #![feature(unsize)]
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use anyhow::{Context, Result};
 use async_trait::async_trait;
 use clap::Parser;
 use protocol::{ClientPacket, ServerPacket};
+use rand::Rng;
 use tokio::{
     net::TcpListener,
     sync::mpsc::{UnboundedReceiver, UnboundedSender},
 };
 use tokio_tungstenite::accept_async;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
 use tracing::{error, info};
 
-use crate::process::{Process, WebSocketComm};
+use crate::{
+    process::{Process, WebSocketComm},
+    tls::{MaybeTlsClientStream, MaybeTlsStream},
+};
 
 mod command;
 mod process;
+mod tls;
 
 #[derive(Parser)]
 pub struct Args {
@@ -25,6 +31,21 @@ pub struct Args {
 
     #[clap(short, long, default_value = "8080")]
     pub port: u16,
+
+    /// Path to a PEM certificate chain; enables TLS together with `tls_key`.
+    #[cfg(feature = "tls")]
+    #[clap(long)]
+    pub tls_cert: Option<std::path::PathBuf>,
+
+    /// Path to the PEM private key matching `tls_cert`.
+    #[cfg(feature = "tls")]
+    #[clap(long)]
+    pub tls_key: Option<std::path::PathBuf>,
+
+    /// Pre-built TLS server config, taking priority over `tls_cert`/`tls_key`.
+    #[cfg(feature = "tls")]
+    #[clap(skip)]
+    pub tls_config: Option<Arc<tokio_rustls::rustls::ServerConfig>>,
 }
 
 #[derive(Debug, Clone)]
@@ -38,6 +59,35 @@ pub trait Comm {
     async fn recv(&mut self) -> Result<ClientPacket>;
 }
 
+/// The send half of a [`Comm`] split via [`CommSplit::split`].
+#[async_trait]
+pub trait CommTx: Send {
+    async fn send(&mut self, packet: ServerPacket) -> Result<()>;
+}
+
+/// The receive half of a [`Comm`] split via [`CommSplit::split`].
+#[async_trait]
+pub trait CommRx: Send {
+    async fn recv(&mut self) -> Result<ClientPacket>;
+}
+
+#[async_trait]
+impl CommTx for Box<dyn CommTx + Send> {
+    async fn send(&mut self, packet: ServerPacket) -> Result<()> {
+        (**self).send(packet).await
+    }
+}
+
+/// Implemented by [`Comm`]s that can be torn into independent send/receive
+/// halves, so a reader and a writer can be driven from separate tasks
+/// instead of fighting over one `&mut self`.
+pub trait CommSplit: Comm + Sized {
+    type Tx: CommTx;
+    type Rx: CommRx;
+
+    fn split(self) -> (Self::Tx, Self::Rx);
+}
+
 struct SimpleComm {
     tx: UnboundedSender<ServerPacket>,
     rx: UnboundedReceiver<ClientPacket>,
@@ -55,6 +105,34 @@ impl Comm for SimpleComm {
     }
 }
 
+struct SimpleCommTx(UnboundedSender<ServerPacket>);
+
+#[async_trait]
+impl CommTx for SimpleCommTx {
+    async fn send(&mut self, packet: ServerPacket) -> Result<()> {
+        self.0.send(packet)?;
+        Ok(())
+    }
+}
+
+struct SimpleCommRx(UnboundedReceiver<ClientPacket>);
+
+#[async_trait]
+impl CommRx for SimpleCommRx {
+    async fn recv(&mut self) -> Result<ClientPacket> {
+        self.0.recv().await.context("Failed to receive packet")
+    }
+}
+
+impl CommSplit for SimpleComm {
+    type Tx = SimpleCommTx;
+    type Rx = SimpleCommRx;
+
+    fn split(self) -> (Self::Tx, Self::Rx) {
+        (SimpleCommTx(self.tx), SimpleCommRx(self.rx))
+    }
+}
+
 /// Launch using [`SimpleComm`] and return (tx, rx) for sending and receiving packets.
 pub fn launch() -> Result<(
     UnboundedSender<ClientPacket>,
@@ -67,16 +145,56 @@ pub fn launch() -> Result<(
 
     let comm = SimpleComm { tx: tx1, rx: rx2 };
 
+    // `launch` has no shutdown handle of its own, so this connection never
+    // gets force-closed from the outside.
+    let shutdown = CancellationToken::new();
+
     tokio::spawn(async move {
-        handle_client(executor, comm).await;
+        handle_client(executor, comm, shutdown).await;
     });
 
     Ok((tx2, rx1))
 }
 
+/// A handle to a listener spawned by [`launch_websocket`].
+///
+/// Dropping this has no effect on the listener; call [`ListenerHandle::close`]
+/// (or [`ListenerHandle::close_on`]) to actually shut it down.
+pub struct ListenerHandle {
+    shutdown: CancellationToken,
+}
+
+impl ListenerHandle {
+    /// Signal the listener to stop accepting new connections and wind down
+    /// in-flight clients. Returns immediately; the listener's own task waits
+    /// for those clients to finish.
+    pub fn close(&self) {
+        self.shutdown.cancel();
+    }
+
+    /// Close the listener once `fut` resolves.
+    pub fn close_on<F>(&self, fut: F)
+    where
+        F: std::future::Future<Output = ()> + Send + 'static,
+    {
+        let shutdown = self.shutdown.clone();
+        tokio::spawn(async move {
+            fut.await;
+            shutdown.cancel();
+        });
+    }
+}
+
 #[must_use]
-pub fn launch_websocket(args: Args) -> UnboundedReceiver<Event> {
+pub fn launch_websocket(args: Args) -> (UnboundedReceiver<Event>, ListenerHandle) {
     let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    let shutdown = CancellationToken::new();
+    let tracker = TaskTracker::new();
+
+    let handle = ListenerHandle {
+        shutdown: shutdown.clone(),
+    };
+
     tokio::spawn(async move {
         info!("Starting executor");
 
@@ -88,9 +206,24 @@ pub fn launch_websocket(args: Args) -> UnboundedReceiver<Event> {
             }
         };
 
-        let Args { ip, port } = args;
+        #[cfg(feature = "tls")]
+        let acceptor = match (&args.tls_config, &args.tls_cert, &args.tls_key) {
+            (Some(config), _, _) => Some(tokio_rustls::TlsAcceptor::from(config.clone())),
+            (None, Some(cert), Some(key)) => match tls::acceptor_from_paths(cert, key) {
+                Ok(acceptor) => Some(acceptor),
+                Err(e) => {
+                    error!("Failed to configure TLS: {e}");
+                    return;
+                }
+            },
+            (None, None, None) => None,
+            (None, _, _) => {
+                error!("Both `tls_cert` and `tls_key` must be set to enable TLS");
+                return;
+            }
+        };
 
-        let addr = format!("{ip}:{port}");
+        let addr = format!("{}:{}", args.ip, args.port);
 
         let listener = match TcpListener::bind(&addr).await {
             Ok(v) => v,
@@ -107,19 +240,37 @@ pub fn launch_websocket(args: Args) -> UnboundedReceiver<Event> {
         info!("Listening on: {addr}");
 
         loop {
-            let (socket, _) = match listener.accept().await {
-                Ok(v) => v,
-                Err(e) => {
-                    error!("{e}");
-                    return;
-                }
+            let (socket, _) = tokio::select! {
+                biased;
+                () = shutdown.cancelled() => break,
+                accepted = listener.accept() => match accepted {
+                    Ok(v) => v,
+                    Err(e) => {
+                        error!("{e}");
+                        break;
+                    }
+                },
             };
 
+            #[cfg(feature = "tls")]
+            let socket = match &acceptor {
+                Some(acceptor) => match acceptor.accept(socket).await {
+                    Ok(tls_stream) => MaybeTlsStream::Tls(tls_stream),
+                    Err(e) => {
+                        error!("TLS handshake failed: {e}");
+                        continue;
+                    }
+                },
+                None => MaybeTlsStream::Plain(socket),
+            };
+            #[cfg(not(feature = "tls"))]
+            let socket = MaybeTlsStream::Plain(socket);
+
             let ws_stream = match accept_async(socket).await {
                 Ok(v) => v,
                 Err(e) => {
                     error!("{e}");
-                    return;
+                    continue;
                 }
             };
 
@@ -134,20 +285,255 @@ pub fn launch_websocket(args: Args) -> UnboundedReceiver<Event> {
             let ws = WebSocketComm::new(ws_stream);
 
             let executor = executor.clone();
-            tokio::spawn(async move {
-                handle_client(executor, ws).await;
+            let shutdown = shutdown.clone();
+            tracker.spawn(async move {
+                handle_client(executor, ws, shutdown).await;
             });
         }
+
+        info!("Shutting down listener, waiting for in-flight clients");
+        tracker.close();
+        tracker.wait().await;
     });
 
-    rx
+    (rx, handle)
+}
+
+const INITIAL_RECONNECT_BACKOFF: Duration = Duration::from_millis(250);
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Double `current`, capped at [`MAX_RECONNECT_BACKOFF`] — pulled out of the
+/// reconnect loop so the capping itself is unit-testable without driving a
+/// real connection attempt.
+fn next_backoff(current: Duration) -> Duration {
+    (current * 2).min(MAX_RECONNECT_BACKOFF)
+}
+
+/// TLS configuration for [`launch_websocket_connect`] when dialing a
+/// `wss://` hub — the connector-side counterpart to [`Args`]'s
+/// `tls_cert`/`tls_key`.
+#[derive(Default, Clone)]
+pub struct ConnectTls {
+    /// An extra CA certificate (PEM) to trust, e.g. a private hub's
+    /// self-signed certificate. Publicly-trusted CAs are always accepted.
+    /// Ignored unless dialing a `wss://` URL.
+    #[cfg(feature = "tls")]
+    pub ca_cert: Option<std::path::PathBuf>,
+}
+
+/// Dial `url`, performing a TLS handshake first if it's `wss://`.
+async fn dial(
+    url: &str,
+    tls_config: &ConnectTls,
+) -> Result<tokio_tungstenite::WebSocketStream<MaybeTlsClientStream>> {
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    let request = url.into_client_request()?;
+    let is_wss = request.uri().scheme_str() == Some("wss");
+    let host = request
+        .uri()
+        .host()
+        .context("WebSocket URL has no host")?
+        .to_string();
+    let port = request
+        .uri()
+        .port_u16()
+        .unwrap_or(if is_wss { 443 } else { 80 });
+
+    let tcp = tokio::net::TcpStream::connect((host.as_str(), port)).await?;
+
+    #[cfg(feature = "tls")]
+    let socket = if is_wss {
+        let connector = tls::connector_from_ca_cert(tls_config.ca_cert.as_deref())?;
+        let domain = tokio_rustls::rustls::pki_types::ServerName::try_from(host)
+            .context("Invalid TLS server name")?;
+        MaybeTlsClientStream::Tls(connector.connect(domain, tcp).await?)
+    } else {
+        MaybeTlsClientStream::Plain(tcp)
+    };
+    #[cfg(not(feature = "tls"))]
+    let socket = {
+        anyhow::ensure!(!is_wss, "Dialing a wss:// URL requires the `tls` feature");
+        MaybeTlsClientStream::Plain(tcp)
+    };
+
+    let (ws_stream, _) = tokio_tungstenite::client_async(request, socket).await?;
+    Ok(ws_stream)
+}
+
+/// Dial `url` instead of listening, running the same [`handle_client`] loop
+/// over the resulting connection. On error or disconnect, reconnects with
+/// capped exponential backoff and jitter, re-emitting [`Event::Connected`]
+/// on every successful (re)connection.
+///
+/// This is the reverse of [`launch_websocket`]: useful for deploying behind
+/// NAT/firewalls by dialing out to a central hub instead of exposing a
+/// listening port.
+#[must_use]
+pub fn launch_websocket_connect(
+    url: String,
+    tls_config: ConnectTls,
+) -> (UnboundedReceiver<Event>, ListenerHandle) {
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    let shutdown = CancellationToken::new();
+
+    let handle = ListenerHandle {
+        shutdown: shutdown.clone(),
+    };
+
+    tokio::spawn(async move {
+        info!("Starting executor (outbound to {url})");
+
+        let executor = match Executor::new() {
+            Ok(v) => v,
+            Err(e) => {
+                error!("{e}");
+                return;
+            }
+        };
+
+        let mut backoff = INITIAL_RECONNECT_BACKOFF;
+        let tracker = TaskTracker::new();
+
+        'reconnect: loop {
+            let ws_stream = loop {
+                let connected = tokio::select! {
+                    biased;
+                    () = shutdown.cancelled() => break 'reconnect,
+                    connected = dial(&url, &tls_config) => connected,
+                };
+
+                match connected {
+                    Ok(stream) => break stream,
+                    Err(e) => {
+                        error!("Failed to connect to {url}: {e}");
+
+                        let jitter = Duration::from_millis(
+                            rand::thread_rng().gen_range(0..=backoff.as_millis() as u64),
+                        );
+
+                        tokio::select! {
+                            biased;
+                            () = shutdown.cancelled() => break 'reconnect,
+                            () = tokio::time::sleep(backoff + jitter) => {},
+                        }
+
+                        backoff = next_backoff(backoff);
+                    }
+                }
+            };
+
+            info!("Connected to {url}");
+            backoff = INITIAL_RECONNECT_BACKOFF;
+
+            if tx.send(Event::Connected).is_err() {
+                error!("Failed to send connected event.");
+            }
+
+            let ws = WebSocketComm::new(ws_stream);
+            let executor = executor.clone();
+            let connection_shutdown = shutdown.clone();
+            let mut client = tracker.spawn(async move {
+                handle_client(executor, ws, connection_shutdown).await;
+            });
+
+            let shutdown_requested = tokio::select! {
+                biased;
+                () = shutdown.cancelled() => true,
+                result = &mut client => {
+                    if let Err(e) = result {
+                        error!("Outbound connection task panicked: {e}");
+                    }
+                    false
+                }
+            };
+
+            if shutdown_requested {
+                info!("Shutting down outbound connection, waiting for it to finish");
+                let _ = client.await;
+                break 'reconnect;
+            }
+
+            let jitter = Duration::from_millis(
+                rand::thread_rng().gen_range(0..=backoff.as_millis() as u64),
+            );
+
+            tokio::select! {
+                biased;
+                () = shutdown.cancelled() => break 'reconnect,
+                () = tokio::time::sleep(backoff + jitter) => {},
+            }
+
+            backoff = next_backoff(backoff);
+        }
+
+        tracker.close();
+        tracker.wait().await;
+        info!("Outbound connection loop stopped");
+    });
+
+    (rx, handle)
 }
 
 type Ctx = Arc<Inner>;
 
+/// Identifies one accepted connection for as long as it's registered in
+/// [`ConnectionRegistry::connections`].
+pub type ConnectionId = u64;
+
+/// Tracks registered connections' outbound senders so one connection can
+/// address or broadcast to others. Kept independent of [`Inner`]'s AI/HTTP
+/// clients so it can be exercised directly in tests without standing up a
+/// full [`Executor`].
+#[derive(Default)]
+struct ConnectionRegistry {
+    next_id: std::sync::atomic::AtomicU64,
+    connections: dashmap::DashMap<ConnectionId, command::rpc::SharedTx>,
+}
+
+impl ConnectionRegistry {
+    /// Register a connection's outbound sender so other connections can
+    /// address or broadcast packets to it; returns its id.
+    fn register(&self, tx: command::rpc::SharedTx) -> ConnectionId {
+        let id = self
+            .next_id
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        self.connections.insert(id, tx);
+        id
+    }
+
+    fn unregister(&self, id: ConnectionId) {
+        self.connections.remove(&id);
+    }
+
+    /// Send `packet` to a specific connection, if it's still registered.
+    /// Returns whether it was delivered.
+    async fn send_to(&self, id: ConnectionId, packet: ServerPacket) -> bool {
+        let Some(tx) = self.connections.get(&id).map(|entry| entry.value().clone()) else {
+            return false;
+        };
+
+        tx.lock().await.send(packet).await.is_ok()
+    }
+
+    /// Send `packet` to every currently registered connection.
+    async fn broadcast(&self, packet: ServerPacket) {
+        let senders: Vec<_> = self
+            .connections
+            .iter()
+            .map(|entry| entry.value().clone())
+            .collect();
+
+        for tx in senders {
+            let _ = tx.lock().await.send(packet.clone()).await;
+        }
+    }
+}
+
 struct Inner {
     ai: tokio_openai::Client,
     req: reqwest::Client,
+    registry: ConnectionRegistry,
 }
 
 #[derive(Clone)]
@@ -160,6 +546,7 @@ fn ctx() -> Result<Ctx> {
     let inner = Inner {
         ai: tokio_openai::Client::simple()?,
         req: reqwest::Client::new(),
+        registry: ConnectionRegistry::default(),
     };
 
     Ok(Arc::new(inner))
@@ -169,12 +556,141 @@ impl Executor {
     fn new() -> Result<Self> {
         Ok(Self { ctx: ctx()? })
     }
+
+    pub(crate) fn register_connection(&self, tx: command::rpc::SharedTx) -> ConnectionId {
+        self.ctx.registry.register(tx)
+    }
+
+    pub(crate) fn unregister_connection(&self, id: ConnectionId) {
+        self.ctx.registry.unregister(id);
+    }
+
+    pub(crate) async fn send_to(&self, id: ConnectionId, packet: ServerPacket) -> bool {
+        self.ctx.registry.send_to(id, packet).await
+    }
+
+    pub(crate) async fn broadcast(&self, packet: ServerPacket) {
+        self.ctx.registry.broadcast(packet).await;
+    }
 }
 
-async fn handle_client(executor: Executor, comm: impl Comm + Send) {
-    let process = Process::new(executor, comm);
+async fn handle_client<C>(executor: Executor, comm: C, shutdown: CancellationToken)
+where
+    C: Comm + CommSplit + Send,
+    C::Tx: 'static,
+    C::Rx: 'static,
+{
+    let process = Process::new(executor, comm, shutdown);
 
     if let Err(e) = process.run().await {
         error!("Error: {}", e);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn close_cancels_the_shutdown_token() {
+        let shutdown = CancellationToken::new();
+        let handle = ListenerHandle {
+            shutdown: shutdown.clone(),
+        };
+
+        assert!(!shutdown.is_cancelled());
+        handle.close();
+        assert!(shutdown.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn close_on_cancels_once_the_future_resolves() {
+        let shutdown = CancellationToken::new();
+        let handle = ListenerHandle {
+            shutdown: shutdown.clone(),
+        };
+        let (tx, rx) = tokio::sync::oneshot::channel();
+
+        handle.close_on(async {
+            let _ = rx.await;
+        });
+        assert!(!shutdown.is_cancelled());
+
+        tx.send(()).unwrap();
+        shutdown.cancelled().await;
+    }
+
+    #[test]
+    fn next_backoff_doubles() {
+        let backoff = Duration::from_secs(1);
+        assert_eq!(next_backoff(backoff), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn next_backoff_caps_at_the_maximum() {
+        let backoff = MAX_RECONNECT_BACKOFF;
+        assert_eq!(next_backoff(backoff), MAX_RECONNECT_BACKOFF);
+
+        let almost_max = MAX_RECONNECT_BACKOFF - Duration::from_secs(1);
+        assert_eq!(next_backoff(almost_max), MAX_RECONNECT_BACKOFF);
+    }
+
+    struct RecordingTx(tokio::sync::mpsc::UnboundedSender<ServerPacket>);
+
+    #[async_trait]
+    impl CommTx for RecordingTx {
+        async fn send(&mut self, packet: ServerPacket) -> Result<()> {
+            self.0.send(packet)?;
+            Ok(())
+        }
+    }
+
+    fn recording_tx(
+        tx: tokio::sync::mpsc::UnboundedSender<ServerPacket>,
+    ) -> command::rpc::SharedTx {
+        Arc::new(tokio::sync::Mutex::new(Box::new(RecordingTx(tx))))
+    }
+
+    #[tokio::test]
+    async fn send_to_delivers_only_to_the_registered_connection() {
+        let registry = ConnectionRegistry::default();
+
+        let (tx1, mut rx1) = tokio::sync::mpsc::unbounded_channel();
+        let (tx2, mut rx2) = tokio::sync::mpsc::unbounded_channel();
+        let id1 = registry.register(recording_tx(tx1));
+        let id2 = registry.register(recording_tx(tx2));
+
+        let delivered = registry.send_to(id1, ServerPacket::Text("hi".into())).await;
+        assert!(delivered);
+        match rx1.recv().await {
+            Some(ServerPacket::Text(s)) => assert_eq!(s, "hi"),
+            _ => panic!("expected a text packet on the registered connection"),
+        }
+        assert!(rx2.try_recv().is_err());
+
+        registry.unregister(id2);
+        let delivered = registry
+            .send_to(id2, ServerPacket::Text("gone".into()))
+            .await;
+        assert!(!delivered);
+    }
+
+    #[tokio::test]
+    async fn broadcast_reaches_every_registered_connection() {
+        let registry = ConnectionRegistry::default();
+
+        let (tx1, mut rx1) = tokio::sync::mpsc::unbounded_channel();
+        let (tx2, mut rx2) = tokio::sync::mpsc::unbounded_channel();
+        registry.register(recording_tx(tx1));
+        registry.register(recording_tx(tx2));
+
+        registry.broadcast(ServerPacket::Text("all".into())).await;
+
+        for rx in [&mut rx1, &mut rx2] {
+            match rx.recv().await {
+                Some(ServerPacket::Text(s)) => assert_eq!(s, "all"),
+                _ => panic!("expected every registered connection to get the broadcast"),
+            }
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use futures_util::{
+    stream::{SplitSink, SplitStream},
+    SinkExt, Stream, StreamExt,
+};
+use protocol::{ClientPacket, ServerPacket};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+    sync::Mutex,
+};
+use tokio_tungstenite::{
+    tungstenite::{Error as WsError, Message},
+    WebSocketStream,
+};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+
+use crate::{
+    command::{self, rpc},
+    Comm, CommRx, CommSplit, CommTx, ConnectionId, Executor,
+};
+
+fn encode(packet: &ServerPacket) -> Result<Message> {
+    Ok(Message::Text(serde_json::to_string(packet)?))
+}
+
+async fn decode_next(
+    stream: &mut (impl Stream<Item = Result<Message, WsError>> + Unpin),
+) -> Result<ClientPacket> {
+    loop {
+        let msg = stream
+            .next()
+            .await
+            .context("WebSocket connection closed")??;
+
+        match msg {
+            Message::Text(text) => return Ok(serde_json::from_str(&text)?),
+            Message::Close(_) => anyhow::bail!("WebSocket connection closed"),
+            _ => continue,
+        }
+    }
+}
+
+/// Marker bound for the stream types a [`WebSocketComm`] can wrap: a plain
+/// [`TcpStream`] when listening/dialing over `ws://`, or a TLS stream when
+/// over `wss://`.
+pub trait WsStream: AsyncRead + AsyncWrite + Unpin + Send + 'static {}
+impl<S: AsyncRead + AsyncWrite + Unpin + Send + 'static> WsStream for S {}
+
+pub struct WebSocketComm<S = TcpStream> {
+    ws: WebSocketStream<S>,
+}
+
+impl<S: WsStream> WebSocketComm<S> {
+    pub fn new(ws: WebSocketStream<S>) -> Self {
+        Self { ws }
+    }
+}
+
+#[async_trait]
+impl<S: WsStream> Comm for WebSocketComm<S> {
+    async fn send(&mut self, packet: ServerPacket) -> Result<()> {
+        self.ws.send(encode(&packet)?).await?;
+        Ok(())
+    }
+
+    async fn recv(&mut self) -> Result<ClientPacket> {
+        decode_next(&mut self.ws).await
+    }
+}
+
+pub struct WebSocketCommTx<S>(SplitSink<WebSocketStream<S>, Message>);
+
+#[async_trait]
+impl<S: WsStream> CommTx for WebSocketCommTx<S> {
+    async fn send(&mut self, packet: ServerPacket) -> Result<()> {
+        self.0.send(encode(&packet)?).await?;
+        Ok(())
+    }
+}
+
+pub struct WebSocketCommRx<S>(SplitStream<WebSocketStream<S>>);
+
+#[async_trait]
+impl<S: WsStream> CommRx for WebSocketCommRx<S> {
+    async fn recv(&mut self) -> Result<ClientPacket> {
+        decode_next(&mut self.0).await
+    }
+}
+
+impl<S: WsStream> CommSplit for WebSocketComm<S> {
+    type Tx = WebSocketCommTx<S>;
+    type Rx = WebSocketCommRx<S>;
+
+    fn split(self) -> (Self::Tx, Self::Rx) {
+        let (sink, stream) = self.ws.split();
+        (WebSocketCommTx(sink), WebSocketCommRx(stream))
+    }
+}
+
+pub struct Process<C> {
+    executor: Executor,
+    comm: C,
+    shutdown: CancellationToken,
+}
+
+impl<C> Process<C>
+where
+    C: Comm + CommSplit + Send,
+    C::Tx: 'static,
+    C::Rx: 'static,
+{
+    /// `shutdown` lets the owning listener/connector wind this connection
+    /// down even if the client itself never sends anything and never
+    /// disconnects; pass [`CancellationToken::new()`] for a caller that has
+    /// no such concept and never wants to force-close connections.
+    pub fn new(executor: Executor, comm: C, shutdown: CancellationToken) -> Self {
+        Self {
+            executor,
+            comm,
+            shutdown,
+        }
+    }
+
+    pub async fn run(self) -> Result<()> {
+        let Process {
+            executor,
+            comm,
+            shutdown,
+        } = self;
+        let (tx, mut rx) = comm.split();
+        let tx: rpc::SharedTx = Arc::new(Mutex::new(Box::new(tx)));
+        let subs = Arc::new(rpc::Subscriptions::new());
+        // Tracks every task spawned on this connection's behalf: one per
+        // dispatched request, plus one per open subscription's poll loop.
+        // Both kinds hold a `tx.clone()`, so both must finish before the
+        // `Arc::try_unwrap(tx)` below can succeed.
+        let tasks = TaskTracker::new();
+
+        let connection_id = executor.register_connection(tx.clone());
+        if let Err(e) = rpc::notify_registered(&tx, connection_id).await {
+            executor.unregister_connection(connection_id);
+            return Err(e);
+        }
+
+        let exit = Self::run_loop(
+            &executor,
+            &tx,
+            &mut rx,
+            &subs,
+            &tasks,
+            connection_id,
+            &shutdown,
+        )
+        .await;
+        executor.unregister_connection(connection_id);
+
+        subs.cancel_all();
+        tasks.close();
+        tasks.wait().await;
+
+        match exit {
+            LoopExit::Shutdown => Ok(()),
+            LoopExit::Error(e) => Err(e),
+            LoopExit::SwitchToPty => {
+                tx.lock()
+                    .await
+                    .send(ServerPacket::Text("pty started".into()))
+                    .await?;
+                let tx = Arc::try_unwrap(tx).map_err(|_| {
+                    anyhow::anyhow!(
+                        "cannot enter PTY mode: connection's sender is still referenced"
+                    )
+                })?;
+                command::run_pty_session(tx.into_inner(), rx).await
+            }
+        }
+    }
+
+    /// Drives the connection: reads packets off `rx` and dispatches each
+    /// request in its own task (tracked in `tasks`, alongside any
+    /// subscriptions it opens), so a slow `dispatch` call (a long-running
+    /// `command::handle`, a subscription tick) never holds up reading the
+    /// *next* packet — e.g. an `unsubscribe` arriving while an earlier
+    /// request is still in flight.
+    ///
+    /// Also watches `shutdown`, so an idle connection (one that never sends
+    /// anything and never disconnects on its own) still winds down once the
+    /// owning listener/connector is told to shut down, instead of only
+    /// gating new connections.
+    async fn run_loop(
+        executor: &Executor,
+        tx: &rpc::SharedTx,
+        rx: &mut C::Rx,
+        subs: &Arc<rpc::Subscriptions>,
+        tasks: &TaskTracker,
+        connection_id: ConnectionId,
+        shutdown: &CancellationToken,
+    ) -> LoopExit {
+        let (dispatch_err_tx, mut dispatch_err_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        loop {
+            tokio::select! {
+                biased;
+                () = shutdown.cancelled() => return LoopExit::Shutdown,
+                Some(e) = dispatch_err_rx.recv() => return LoopExit::Error(e),
+                packet = rx.recv() => {
+                    let packet = match packet {
+                        Ok(v) => v,
+                        Err(e) => return LoopExit::Error(e),
+                    };
+
+                    if command::is_pty_request(&packet) {
+                        return LoopExit::SwitchToPty;
+                    }
+
+                    let requests = match rpc::parse_batch(&packet) {
+                        Ok(v) => v,
+                        Err(e) => return LoopExit::Error(e),
+                    };
+
+                    for request in requests {
+                        let executor = executor.clone();
+                        let tx = tx.clone();
+                        let subs = subs.clone();
+                        let tasks_for_subscription = tasks.clone();
+                        let dispatch_err_tx = dispatch_err_tx.clone();
+
+                        tasks.spawn(async move {
+                            if let Err(e) = rpc::dispatch(
+                                &executor,
+                                request,
+                                &tx,
+                                &subs,
+                                &tasks_for_subscription,
+                                connection_id,
+                            )
+                            .await
+                            {
+                                let _ = dispatch_err_tx.send(e);
+                            }
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+enum LoopExit {
+    Error(anyhow::Error),
+    SwitchToPty,
+    Shutdown,
+}